@@ -26,28 +26,268 @@ use pyo3::prelude::*;
 use rayon::prelude::*;
 
 use geojson::quick_collection;
-use geo_types::{Geometry, Coordinate, Point};
+use geo_types::{Geometry, Coordinate, Point, Rect};
 use geojson::GeoJson;
 use geo::algorithm::contains::Contains;
+use geo::algorithm::bounding_rect::BoundingRect;
+use geo::algorithm::intersects::Intersects;
+use geo::algorithm::lines_iter::LinesIter;
+
+use rstar::{RTree, RTreeObject, PointDistance, AABB, Envelope};
+use roaring::RoaringBitmap;
+use proj::Proj;
+use wkb::wkb_to_geom;
+use xz2::read::XzDecoder;
+use std::io::Read;
 
 use numpy::{PyArray, PyReadonlyArray1, ToPyArray};
 
+/// Conservative (i.e. never larger than the true value anywhere on the
+/// WGS84 ellipsoid) metres-per-degree-of-latitude figure. Longitude is not
+/// given a single constant here, since a degree of longitude shrinks
+/// towards the poles - see `envelope_lower_bound_m`, which scales it by
+/// `cos(latitude)` instead
+const METRES_PER_DEGREE_LAT: f64 = 110_500.0;
+
+/// Node stored in the R-tree: just the polygon's index and its bounding
+/// rectangle, so the tree itself never needs to touch the (potentially huge)
+/// `Geometry` values directly.
+struct IndexedEnvelope {
+    index: usize,
+    envelope: AABB<[f64; 2]>,
+}
+
+impl RTreeObject for IndexedEnvelope {
+    type Envelope = AABB<[f64; 2]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        self.envelope
+    }
+}
+
+impl PointDistance for IndexedEnvelope {
+    fn distance_2(&self, point: &[f64; 2]) -> f64 {
+        self.envelope.distance_2(point)
+    }
+
+    fn contains_point(&self, point: &[f64; 2]) -> bool {
+        self.envelope.contains_point(point)
+    }
+}
+
 #[pyclass]
 struct Engine {
     // One Geometry per input GeoJson string
-    polygons: Vec<Geometry<f64>>
+    polygons: Vec<Geometry<f64>>,
+
+    // Bulk-loaded R-tree over the polygons' bounding rectangles, used to
+    // narrow `pip_1` down to a handful of candidates before running the
+    // exact containment test
+    rtree: RTree<IndexedEnvelope>,
+
+    // Bounding rectangle for each polygon, in the same order as `polygons`.
+    // Feeds `RasterMask::build`'s bbox-overlap quick reject; `build_rtree`
+    // computes the same rectangles from the polygons on its own
+    bounding_boxes: Vec<Rect<f64>>,
+
+    // Optional rasterized acceleration layer built by `new_with_mask`. When
+    // present, `pip_1` consults it before falling back to the R-tree/exact
+    // path
+    mask: Option<RasterMask>,
+
+    // EPSG code query points arrive in, set by `new_with_crs`, used by
+    // `pip_1_with_crs`/`pip_n_with_crs` to rebuild a reprojection pipeline
+    // on demand. We can't cache the `Proj` itself here: it wraps raw `PJ`/
+    // `PJ_CONTEXT` pointers, so it's neither `Send` nor `Sync`, and even
+    // behind a `Mutex` that would make `Engine` itself non-`Sync` - which
+    // `pip_n_threaded` relies on to share `&self` across rayon threads.
+    // A `String` costs nothing to keep `Send + Sync`, at the price of
+    // rebuilding the pipeline per call/batch instead of once overall
+    source_epsg: Option<String>,
+}
+
+/// A regular lat/lon grid laid over the union of all polygon bounding
+/// boxes, with each cell pre-classified as fully inside a single polygon,
+/// fully outside every polygon, or mixed/boundary. Cells are addressed by
+/// a linear id (`row * width + col`), mirroring the roaring-landmask
+/// approach of keying compact bitmaps by cell id.
+struct RasterMask {
+    cell_size: f64,
+    min_x: f64,
+    min_y: f64,
+    width: usize,
+    height: usize,
+
+    // Cells fully outside every polygon
+    outside: RoaringBitmap,
+
+    // One bitmap per polygon, holding the cells fully inside that polygon
+    inside: Vec<RoaringBitmap>,
+}
+
+impl RasterMask {
+    /// Classifies every grid cell over the union of `bounding_boxes`
+    /// against `polygons`.
+    ///
+    /// A cell is only ever marked fully inside or fully outside a polygon
+    /// when that is *proven*: a polygon whose boundary doesn't cross the
+    /// cell rectangle at all can't have a partial overlap with it, so the
+    /// cell must lie entirely on one side of that polygon's boundary, which
+    /// a single interior sample point then distinguishes. Point-sampling a
+    /// handful of locations (as an earlier version of this did) is not
+    /// sufficient on its own: a concave polygon or a hole can dip between
+    /// samples, so this always backs containment with the boundary-crossing
+    /// check first.
+    ///
+    /// Scanning polygons in index order also preserves `pip_1`'s "lowest
+    /// index among hits" semantics: as soon as a lower-index polygon's
+    /// boundary crosses the cell, the cell is left as mixed/boundary even if
+    /// a later, higher-index polygon fully contains it, because that lower
+    /// polygon might still contain whichever point is actually queried
+    fn build(polygons: &[Geometry<f64>], bounding_boxes: &[Rect<f64>], cell_size: f64) -> RasterMask {
+        let min_x = bounding_boxes.iter().map(|rect| rect.min().x)
+            .fold(f64::INFINITY, f64::min);
+        let min_y = bounding_boxes.iter().map(|rect| rect.min().y)
+            .fold(f64::INFINITY, f64::min);
+        let max_x = bounding_boxes.iter().map(|rect| rect.max().x)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let max_y = bounding_boxes.iter().map(|rect| rect.max().y)
+            .fold(f64::NEG_INFINITY, f64::max);
+
+        let width = (((max_x - min_x) / cell_size).ceil() as usize).max(1);
+        let height = (((max_y - min_y) / cell_size).ceil() as usize).max(1);
+
+        let mut outside = RoaringBitmap::new();
+        let mut inside = vec![RoaringBitmap::new(); polygons.len()];
+
+        for row in 0..height {
+            for col in 0..width {
+                let cell_id = (row * width + col) as u32;
+
+                let cell_min_x = min_x + col as f64 * cell_size;
+                let cell_min_y = min_y + row as f64 * cell_size;
+                let cell_max_x = cell_min_x + cell_size;
+                let cell_max_y = cell_min_y + cell_size;
+
+                let cell_rect = Rect::new(
+                    Coordinate { x: cell_min_x, y: cell_min_y },
+                    Coordinate { x: cell_max_x, y: cell_max_y },
+                );
+                let cell_polygon = cell_rect.to_polygon();
+                let cell_centre = Point::new(
+                    (cell_min_x + cell_max_x) / 2.0,
+                    (cell_min_y + cell_max_y) / 2.0,
+                );
+
+                let mut fully_inside = None;
+
+                for (index, polygon) in polygons.iter().enumerate() {
+                    // Quick reject: if the bounding boxes don't even
+                    // overlap, this polygon can't touch the cell at all
+                    if !bounding_boxes[index].intersects(&cell_rect) {
+                        continue;
+                    }
+
+                    if polygon.lines_iter().any(|line| line.intersects(&cell_polygon)) {
+                        // The boundary crosses the cell: part of the cell
+                        // could be inside this polygon and part outside it.
+                        // Since this polygon's index is lower than every
+                        // polygon not yet examined, no later polygon can
+                        // resolve the ambiguity either - stop and leave the
+                        // cell for the exact test
+                        fully_inside = None;
+                        break;
+                    }
+
+                    if polygon.contains(&cell_centre) {
+                        // No boundary crossing plus one interior sample
+                        // inside means the whole cell is inside: this is
+                        // the lowest-index polygon that can contain it
+                        fully_inside = Some(index);
+                        break;
+                    }
+
+                    // Bounding boxes overlapped but the boundary doesn't
+                    // cross the cell and the centre isn't contained: this
+                    // polygon provably excludes the whole cell, keep scanning
+                }
+
+                match fully_inside {
+                    Some(index) => { inside[index].insert(cell_id); },
+                    None => {
+                        // Either no polygon touched the cell at all, a
+                        // boundary crossing left it ambiguous, or every
+                        // polygon whose bbox overlaps the cell excludes it.
+                        // Re-run the same bbox reject to decide: only cells
+                        // no bbox overlaps take the outside fast-path; the
+                        // other two cases both fall through to the exact
+                        // test, like mixed/boundary cells
+                        let touched = bounding_boxes.iter().any(|bbox| bbox.intersects(&cell_rect));
+                        if !touched {
+                            outside.insert(cell_id);
+                        }
+                    },
+                }
+            }
+        }
+
+        RasterMask { cell_size, min_x, min_y, width, height, outside, inside }
+    }
+
+    /// Maps a query point to its linear cell id, or `None` if it falls
+    /// outside the rasterized area entirely (in which case the caller
+    /// should fall back to the exact test)
+    fn cell_id(&self, lon: f64, lat: f64) -> Option<u32> {
+        if lon < self.min_x || lat < self.min_y {
+            return None;
+        }
+
+        let col = ((lon - self.min_x) / self.cell_size) as usize;
+        let row = ((lat - self.min_y) / self.cell_size) as usize;
+
+        if col >= self.width || row >= self.height {
+            return None;
+        }
+
+        Some((row * self.width + col) as u32)
+    }
 }
 
-#[pymethods]
 impl Engine {
-    /// Main constructor called by python, takes a geojson string array
-    #[new]
-    fn new(geometry: Vec<&str>) -> Self {
+    /// Bulk-loads an STR-packed R-tree over the bounding rectangles of
+    /// `polygons`, keyed by their index into that Vec.
+    ///
+    /// `RTree::bulk_load` packs the entries top-down with rstar's
+    /// overlap-minimizing (OMT) bulk-loading algorithm, which produces a
+    /// much better balanced tree than inserting one polygon at a time. It
+    /// is not a Sort-Tile-Recursive packing, and the node capacity is
+    /// rstar's default.
+    fn build_rtree(polygons: &[Geometry<f64>]) -> RTree<IndexedEnvelope> {
+        let entries = polygons.iter().enumerate()
+            .map(|(index, polygon)| {
+                let rect = polygon.bounding_rect()
+                    .expect("Could not compute bounding rectangle for polygon");
+
+                let envelope = AABB::from_corners(
+                    [rect.min().x, rect.min().y],
+                    [rect.max().x, rect.max().y],
+                );
+
+                IndexedEnvelope { index, envelope }
+            })
+            .collect();
+
+        RTree::bulk_load(entries)
+    }
+
+    /// Parses one GeoJson string per entry into a single `Geometry` each,
+    /// shared by every constructor that accepts GeoJson input
+    fn parse_geojson(geometry: Vec<&str>) -> Vec<Geometry<f64>> {
         // We use i32 to return the polygon number, ensure we don't have too many polygons
         if geometry.len() > i32::MAX as usize {
             panic!("Too many input polygons");
         }
-        
+
         // Vec for our polygons to test
         let mut polygons = Vec::new();
 
@@ -67,22 +307,392 @@ impl Engine {
             polygons.push(geometry_collection[0].clone());
         }
 
+        polygons
+    }
+
+    /// Builds a reprojection pipeline from `source_epsg` into EPSG:4326.
+    /// `Proj::new_known_crs` normalizes the pipeline for GIS use (axis order
+    /// x/y = easting/northing, i.e. lon/lat for a geographic CRS) regardless
+    /// of the axis order either CRS's authority defines, so `proj.convert`
+    /// always takes and returns `(lon, lat)` - it does not need a
+    /// CRS-specific lat/lon swap here or at any call site
+    fn build_reprojection(source_epsg: &str) -> Proj {
+        Proj::new_known_crs(source_epsg, "EPSG:4326", None)
+            .expect("Could not build reprojection pipeline")
+    }
+
+    /// Reprojects every coordinate of `geometry` in place using `proj`,
+    /// walking each `Geometry` variant down to its underlying coordinates
+    fn reproject(geometry: &mut Geometry<f64>, proj: &Proj) {
+        let reproject_coord = |coordinate: &mut Coordinate<f64>| {
+            let (x, y) = proj.convert((coordinate.x, coordinate.y))
+                .expect("Could not reproject coordinate");
+            coordinate.x = x;
+            coordinate.y = y;
+        };
+
+        match geometry {
+            Geometry::Point(point) => reproject_coord(&mut point.0),
+            Geometry::Line(line) => {
+                reproject_coord(&mut line.start);
+                reproject_coord(&mut line.end);
+            },
+            Geometry::LineString(line_string) => {
+                line_string.0.iter_mut().for_each(reproject_coord);
+            },
+            Geometry::Polygon(polygon) => {
+                polygon.exterior_mut(|ring| ring.0.iter_mut().for_each(reproject_coord));
+                polygon.interiors_mut(|rings| rings.iter_mut()
+                    .for_each(|ring| ring.0.iter_mut().for_each(reproject_coord)));
+            },
+            Geometry::MultiPoint(multi_point) => {
+                multi_point.0.iter_mut().for_each(|point| reproject_coord(&mut point.0));
+            },
+            Geometry::MultiLineString(multi_line_string) => {
+                multi_line_string.0.iter_mut()
+                    .for_each(|line_string| line_string.0.iter_mut().for_each(reproject_coord));
+            },
+            Geometry::MultiPolygon(multi_polygon) => {
+                multi_polygon.0.iter_mut().for_each(|polygon| {
+                    polygon.exterior_mut(|ring| ring.0.iter_mut().for_each(reproject_coord));
+                    polygon.interiors_mut(|rings| rings.iter_mut()
+                        .for_each(|ring| ring.0.iter_mut().for_each(reproject_coord)));
+                });
+            },
+            Geometry::GeometryCollection(geometry_collection) => {
+                geometry_collection.0.iter_mut().for_each(|geometry| Engine::reproject(geometry, proj));
+            },
+            Geometry::Rect(_) | Geometry::Triangle(_) => {
+                panic!("Unsupported geometry type for reprojection");
+            },
+        }
+    }
+
+    /// Builds the reprojection pipeline query points arrive in, from the
+    /// `source_epsg` this `Engine` was built with via `new_with_crs`
+    fn query_reprojection(&self) -> Proj {
+        let source_epsg = self.source_epsg.as_ref()
+            .expect("Engine was not built with new_with_crs, no query CRS to reproject from");
+
+        Engine::build_reprojection(source_epsg)
+    }
+
+    /// Builds an `Engine` from already-parsed polygons, deriving the R-tree
+    /// and bounding boxes shared by every constructor
+    fn from_polygons(polygons: Vec<Geometry<f64>>) -> Self {
         println!("Built Engine with {} polygons", polygons.len());
 
-        Engine { polygons }
+        let rtree = Engine::build_rtree(&polygons);
+        let bounding_boxes = polygons.iter()
+            .map(|polygon| polygon.bounding_rect()
+                .expect("Could not compute bounding rectangle for polygon"))
+            .collect();
+
+        Engine { polygons, rtree, bounding_boxes, mask: None, source_epsg: None }
+    }
+
+    /// Distance in metres from a query point to the given polygon: zero if
+    /// the point is contained, otherwise the minimum geodesic distance from
+    /// the point to one of the polygon's boundary segments (not just its
+    /// ring vertices - the nearest point on a long edge is usually mid-edge)
+    fn distance_to_polygon_m(&self, lat: f64, lon: f64, index: usize) -> f64 {
+        let point = Point(Coordinate { y: lat, x: lon });
+
+        if self.polygons[index].contains(&point) {
+            return 0.0;
+        }
+
+        self.polygons[index].lines_iter()
+            .map(|line| {
+                let closest = Engine::closest_point_on_segment(
+                    Coordinate { x: lon, y: lat }, line.start, line.end);
+                Engine::vincenty_distance_m(lat, lon, closest.y, closest.x)
+            })
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    /// Orthogonally projects `point` onto the segment `start`-`end`,
+    /// clamped to the segment itself. Coordinates are treated as a local
+    /// planar (equirectangular) approximation, which is accurate enough to
+    /// locate the nearest point on a short boundary segment before the
+    /// actual geodesic distance to it is computed with `vincenty_distance_m`
+    fn closest_point_on_segment(
+        point: Coordinate<f64>, start: Coordinate<f64>, end: Coordinate<f64>) -> Coordinate<f64> {
+
+        let segment_dx = end.x - start.x;
+        let segment_dy = end.y - start.y;
+        let segment_len_sq = segment_dx * segment_dx + segment_dy * segment_dy;
+
+        if segment_len_sq == 0.0 {
+            return start; // degenerate (zero-length) segment
+        }
+
+        let t = ((point.x - start.x) * segment_dx + (point.y - start.y) * segment_dy) / segment_len_sq;
+        let t = t.clamp(0.0, 1.0);
+
+        Coordinate { x: start.x + t * segment_dx, y: start.y + t * segment_dy }
+    }
+
+    /// Geodesic distance in metres between two lat/lon points on the WGS84
+    /// ellipsoid, via the Vincenty inverse formula. Falls back to a
+    /// great-circle (haversine) estimate for near-antipodal point pairs,
+    /// where the standard iteration fails to converge
+    fn vincenty_distance_m(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+        const WGS84_A: f64 = 6378137.0;
+        const WGS84_F: f64 = 1.0 / 298.257223563;
+        const WGS84_B: f64 = (1.0 - WGS84_F) * WGS84_A;
+
+        if lat1 == lat2 && lon1 == lon2 {
+            return 0.0;
+        }
+
+        let u1 = ((1.0 - WGS84_F) * lat1.to_radians().tan()).atan();
+        let u2 = ((1.0 - WGS84_F) * lat2.to_radians().tan()).atan();
+        let l = (lon2 - lon1).to_radians();
+
+        let (sin_u1, cos_u1) = u1.sin_cos();
+        let (sin_u2, cos_u2) = u2.sin_cos();
+
+        let mut lambda = l;
+        let mut cos_sq_alpha;
+        let mut sin_sigma;
+        let mut cos_sigma;
+        let mut sigma;
+        let mut cos2_sigma_m;
+
+        let mut iterations_left = 200;
+        loop {
+            let (sin_lambda, cos_lambda) = lambda.sin_cos();
+
+            sin_sigma = ((cos_u2 * sin_lambda).powi(2)
+                + (cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda).powi(2)).sqrt();
+
+            if sin_sigma == 0.0 {
+                return 0.0; // coincident points
+            }
+
+            cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+            sigma = sin_sigma.atan2(cos_sigma);
+
+            let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+            cos_sq_alpha = 1.0 - sin_alpha * sin_alpha;
+
+            cos2_sigma_m = if cos_sq_alpha != 0.0 {
+                cos_sigma - 2.0 * sin_u1 * sin_u2 / cos_sq_alpha
+            } else {
+                0.0 // equatorial line
+            };
+
+            let c = WGS84_F / 16.0 * cos_sq_alpha * (4.0 + WGS84_F * (4.0 - 3.0 * cos_sq_alpha));
+            let lambda_prev = lambda;
+            lambda = l + (1.0 - c) * WGS84_F * sin_alpha * (sigma + c * sin_sigma
+                * (cos2_sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos2_sigma_m * cos2_sigma_m)));
+
+            iterations_left -= 1;
+
+            if (lambda - lambda_prev).abs() < 1e-12 {
+                break;
+            }
+
+            if iterations_left == 0 {
+                // Near-antipodal points: the standard iteration doesn't
+                // converge, fall back to a great-circle estimate
+                return Engine::haversine_distance_m(lat1, lon1, lat2, lon2);
+            }
+        }
+
+        let u_sq = cos_sq_alpha * (WGS84_A * WGS84_A - WGS84_B * WGS84_B) / (WGS84_B * WGS84_B);
+        let cap_a = 1.0 + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+        let cap_b = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+
+        let delta_sigma = cap_b * sin_sigma * (cos2_sigma_m + cap_b / 4.0
+            * (cos_sigma * (-1.0 + 2.0 * cos2_sigma_m * cos2_sigma_m)
+                - cap_b / 6.0 * cos2_sigma_m * (-3.0 + 4.0 * sin_sigma * sin_sigma)
+                    * (-3.0 + 4.0 * cos2_sigma_m * cos2_sigma_m)));
+
+        WGS84_B * cap_a * (sigma - delta_sigma)
+    }
+
+    /// Great-circle distance in metres between two lat/lon points, using
+    /// the mean Earth radius. Only used as a fallback when Vincenty fails
+    /// to converge
+    fn haversine_distance_m(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+        const EARTH_RADIUS_M: f64 = 6371000.0;
+
+        let d_lat = (lat2 - lat1).to_radians();
+        let d_lon = (lon2 - lon1).to_radians();
+
+        let a = (d_lat / 2.0).sin().powi(2)
+            + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+        let c = 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
+
+        EARTH_RADIUS_M * c
+    }
+
+    /// A true lower bound, in metres, on the geodesic distance from
+    /// `(point_lon, point_lat)` to any point inside `envelope`. Used to
+    /// decide when an R-tree-guided nearest-neighbour search can stop
+    /// expanding: once this exceeds the best exact distance found so far,
+    /// no further candidate can possibly be closer.
+    ///
+    /// A degree of latitude is nearly constant in length, so the
+    /// latitudinal gap is scaled by `METRES_PER_DEGREE_LAT`, a figure
+    /// chosen at or below the smallest real value anywhere on the
+    /// ellipsoid. A degree of longitude shrinks towards the poles
+    /// (proportionally to `cos(latitude)`), so the longitudinal gap is
+    /// scaled by the cosine of whichever latitude - the point's own, or the
+    /// envelope edge nearest it - has the larger magnitude, since that's
+    /// where a degree of longitude is shortest and so gives the smallest
+    /// (and therefore still safe) conversion
+    fn envelope_lower_bound_m(point_lon: f64, point_lat: f64, envelope: &AABB<[f64; 2]>) -> f64 {
+        let lower = envelope.lower();
+        let upper = envelope.upper();
+
+        let dlon_deg = if point_lon < lower[0] {
+            lower[0] - point_lon
+        } else if point_lon > upper[0] {
+            point_lon - upper[0]
+        } else {
+            0.0
+        };
+
+        let nearest_lat = if point_lat < lower[1] {
+            lower[1]
+        } else if point_lat > upper[1] {
+            upper[1]
+        } else {
+            point_lat
+        };
+        let dlat_deg = (nearest_lat - point_lat).abs();
+
+        let bounding_lat = if point_lat.abs() > nearest_lat.abs() { point_lat } else { nearest_lat };
+
+        let dlat_m = dlat_deg * METRES_PER_DEGREE_LAT;
+        let dlon_m = dlon_deg * METRES_PER_DEGREE_LAT * bounding_lat.to_radians().cos().abs();
+
+        (dlat_m * dlat_m + dlon_m * dlon_m).sqrt()
+    }
+}
+
+#[pymethods]
+impl Engine {
+    /// Main constructor called by python, takes a geojson string array
+    #[new]
+    fn new(geometry: Vec<&str>) -> Self {
+        let polygons = Engine::parse_geojson(geometry);
+
+        Engine::from_polygons(polygons)
+    }
+
+    /// Constructor which, in addition to parsing the geojson string array
+    /// like `new`, rasterizes a regular lat/lon grid (`cell_size` degrees
+    /// per cell) over the union of all polygon bounding boxes. Each cell is
+    /// classified up front as fully inside a single polygon, fully outside
+    /// every polygon, or mixed/boundary.
+    ///
+    /// `pip_1` then answers most queries from bitmap lookups: one in the
+    /// outside set, then one per polygon's inside set (so an inside answer
+    /// costs up to one lookup per polygon, not O(1)), falling through to
+    /// the exact test only for points landing in boundary cells
+    #[staticmethod]
+    fn new_with_mask(geometry: Vec<&str>, cell_size: f64) -> Self {
+        let polygons = Engine::parse_geojson(geometry);
+
+        let mut engine = Engine::from_polygons(polygons);
+        engine.mask = Some(RasterMask::build(&engine.polygons, &engine.bounding_boxes, cell_size));
+
+        engine
+    }
+
+    /// Constructor for geometry supplied in a CRS other than EPSG:4326.
+    /// Parses the geojson string array like `new`, then reprojects every
+    /// coordinate from `source_epsg` (e.g. "EPSG:3857") into EPSG:4326
+    /// before building the R-tree and bounding boxes, so all query methods
+    /// keep working unchanged on lon/lat input.
+    ///
+    /// `source_epsg` is also recorded on the `Engine` for `pip_1_with_crs`/
+    /// `pip_n_with_crs`, which assume query points arrive in this same CRS
+    #[staticmethod]
+    fn new_with_crs(geometry: Vec<&str>, source_epsg: &str) -> Self {
+        let mut polygons = Engine::parse_geojson(geometry);
+
+        let proj = Engine::build_reprojection(source_epsg);
+
+        for polygon in &mut polygons {
+            Engine::reproject(polygon, &proj);
+        }
+
+        let mut engine = Engine::from_polygons(polygons);
+        engine.source_epsg = Some(source_epsg.to_string());
+
+        engine
+    }
+
+    /// Constructor that decodes Well-Known Binary directly into polygons,
+    /// skipping GeoJSON text parsing entirely. Lets callers ship a single
+    /// precompiled boundary file instead of round-tripping through GeoJSON
+    #[staticmethod]
+    fn from_wkb(blobs: Vec<&[u8]>) -> Self {
+        let polygons = blobs.iter()
+            .map(|blob| wkb_to_geom(&mut &blob[..]).expect("Could not decode WKB"))
+            .collect();
+
+        Engine::from_polygons(polygons)
+    }
+
+    /// Like `from_wkb`, but transparently LZMA/xz-decompresses each blob
+    /// first, for boundary files shipped as `.wkb.xz`
+    #[staticmethod]
+    fn from_wkb_xz(blobs: Vec<&[u8]>) -> Self {
+        let polygons = blobs.iter()
+            .map(|blob| {
+                let mut decompressed = Vec::new();
+                XzDecoder::new(*blob).read_to_end(&mut decompressed)
+                    .expect("Could not decompress xz-compressed WKB blob");
+
+                wkb_to_geom(&mut &decompressed[..]).expect("Could not decode WKB")
+            })
+            .collect();
+
+        Engine::from_polygons(polygons)
     }
 
     /// Method for testing a single point against all our polygons
-    /// 
+    ///
     /// Returns the number of the polygon which had a hit, or -1 if no hit
-    /// 
+    ///
     /// Note this method can be called by Python directly
     fn pip_1(&self, lat: f64, lon: f64) -> i32 {
         let point = Point(Coordinate{y: lat, x: lon});
 
-        // Iterate through our polygons, stopping at the first hit
-        let result = self.polygons.iter()
-            .position(|polygon| polygon.contains(&point));
+        // If a raster mask is present, try to answer straight from it:
+        // a cell fully outside every polygon or fully inside exactly one
+        // settles the query with no geometry test at all. Mixed/boundary
+        // cells (and points outside the rasterized area) fall through to
+        // the R-tree/exact path below
+        if let Some(mask) = &self.mask {
+            if let Some(cell_id) = mask.cell_id(lon, lat) {
+                if mask.outside.contains(cell_id) {
+                    return -1;
+                }
+
+                if let Some(index) = mask.inside.iter().position(|bitmap| bitmap.contains(cell_id)) {
+                    return index as i32;
+                }
+            }
+        }
+
+        // Ask the R-tree for every polygon whose bounding rectangle contains
+        // the point (the R-tree is bulk-loaded from these same bounding
+        // rectangles, so there's no separate bbox pre-filter to apply here),
+        // then run the exact containment test only on those candidates,
+        // keeping the lowest index among hits to preserve the previous
+        // linear-scan semantics
+        let result = self.rtree
+            .locate_all_at_point(&[lon, lat])
+            .map(|candidate| candidate.index)
+            .filter(|&index| self.polygons[index].contains(&point))
+            .min();
 
         // Return the hit number, or -1 if nothing found
         match result {
@@ -91,11 +701,102 @@ impl Engine {
         }
     }
 
+    /// Companion to `pip_1` for a query point supplied in the same
+    /// projected CRS the `Engine` was built with via `new_with_crs`:
+    /// reprojects `(y, x)` into EPSG:4326 and delegates to `pip_1`
+    fn pip_1_with_crs(&self, y: f64, x: f64) -> i32 {
+        let proj = self.query_reprojection();
+        let (lon, lat) = proj.convert((x, y)).expect("Could not reproject query point");
+
+        self.pip_1(lat, lon)
+    }
+
+    /// Batched form of `pip_1_with_crs` for NumPy arrays of coordinates,
+    /// mirroring `pip_n`. Builds the reprojection pipeline once for the
+    /// whole batch rather than once per point
+    fn pip_n_with_crs<'py>(&self,
+        py: Python<'py>,
+        y_array: PyReadonlyArray1<f64>,
+        x_array: PyReadonlyArray1<f64>) -> PyResult<&'py PyArray<i32, ndarray::Dim<[usize; 1]>>> {
+
+        if y_array.len() != x_array.len() {
+            panic!("Input arrays different lengths");
+        }
+
+        let proj = self.query_reprojection();
+        let mut results = Vec::with_capacity(y_array.len());
+
+        for n in 0..y_array.len() {
+            let y = *y_array.get([n]).expect("Error extracting y coordinate");
+            let x = *x_array.get([n]).expect("Error extracting x coordinate");
+
+            let (lon, lat) = proj.convert((x, y)).expect("Could not reproject query point");
+            results.push(self.pip_1(lat, lon));
+        }
+
+        Ok(results.to_pyarray(py))
+    }
+
+    /// Method for testing a single point against all our polygons, returning
+    /// every polygon that contains it rather than stopping at the first hit
+    ///
+    /// Returns the (possibly empty) Vec of polygon indexes which contain the
+    /// point, for overlapping/nested zones where a point can belong to more
+    /// than one polygon
+    fn pip_1_all(&self, lat: f64, lon: f64) -> Vec<i32> {
+        let point = Point(Coordinate{y: lat, x: lon});
+
+        let mut hits: Vec<i32> = self.rtree
+            .locate_all_at_point(&[lon, lat])
+            .map(|candidate| candidate.index)
+            .filter(|&index| self.polygons[index].contains(&point))
+            .map(|index| index as i32)
+            .collect();
+
+        hits.sort_unstable();
+        hits
+    }
+
+    /// Batched form of `pip_1_all` for NumPy arrays of coordinates
+    ///
+    /// Returns a `(indexes, offsets)` pair in CSR layout: the hits for point
+    /// `i` are `indexes[offsets[i]..offsets[i + 1]]`. This is the standard
+    /// layout for a many-to-many spatial join result and avoids returning
+    /// ragged Python lists
+    fn pip_n_all<'py>(&self,
+        py: Python<'py>,
+        lat_array: PyReadonlyArray1<f64>,
+        lon_array: PyReadonlyArray1<f64>)
+        -> PyResult<(&'py PyArray<i32, ndarray::Dim<[usize; 1]>>, &'py PyArray<i32, ndarray::Dim<[usize; 1]>>)> {
+
+        // Ensure the latitude and longitude counts match
+        if lat_array.len() != lon_array.len() {
+            panic!("Input arrays different lengths");
+        }
+
+        // Offsets has one entry per point plus a trailing entry for the
+        // total hit count, per the CSR convention
+        let mut offsets = Vec::with_capacity(lat_array.len() + 1);
+        let mut indexes = Vec::new();
+        offsets.push(0i32);
+
+        // Loop over all the coordinates
+        for n in 0..lat_array.len() {
+            let lat = *lat_array.get([n]).expect("Error extracting lat coordinate");
+            let lon = *lon_array.get([n]).expect("Error extracting lon coordinate");
+
+            indexes.extend(self.pip_1_all(lat, lon));
+            offsets.push(indexes.len() as i32);
+        }
+
+        Ok((indexes.to_pyarray(py), offsets.to_pyarray(py)))
+    }
+
     /// Method for testing NumPy arrays of coordinates against all our polygons,
     /// using a single thread
-    /// 
+    ///
     /// Returns a NumPy array of polygon indexes, -1 values mean no match found
-    /// 
+    ///
     /// Type signature means two 1-dimenstional 64-bit floating point NumPy arrays in,
     /// and one 1-dimensional 32-bit signed integer NumPy array out
     fn pip_n<'py>(&self,
@@ -176,6 +877,73 @@ impl Engine {
         // Return the results Vec as a NumPy array
         Ok(results.to_pyarray(py))
     }
+
+    /// Finds the polygon closest to a query point and its distance
+    ///
+    /// Returns `(index, distance_m)`; `distance_m` is `0.0` when the point
+    /// is contained by that polygon. Candidates are drawn from the R-tree,
+    /// which orders them by planar envelope distance *in degrees* - that
+    /// order does not agree with true geodesic distance in metres once
+    /// latitude varies between candidates (longitude degrees shrink with
+    /// `cos(lat)`), so we cannot stop consuming the iterator early: a
+    /// later (degree-wise) candidate can still be the true nearest in
+    /// metres. We do still use `envelope_lower_bound_m`, a true per-box
+    /// lower bound in metres, to skip the expensive exact distance
+    /// calculation for boxes that provably cannot beat the current best
+    fn nearest(&self, lat: f64, lon: f64) -> (i32, f64) {
+        if self.polygons.is_empty() {
+            panic!("Engine has no polygons to compare against");
+        }
+
+        // A point inside a polygon is distance zero from it, and pip_1
+        // already has the fast paths (mask/R-tree) to find that cheaply
+        let direct_hit = self.pip_1(lat, lon);
+        if direct_hit >= 0 {
+            return (direct_hit, 0.0);
+        }
+
+        let mut best: Option<(usize, f64)> = None;
+
+        for candidate in self.rtree.nearest_neighbor_iter(&[lon, lat]) {
+            if let Some((_, best_distance_m)) = best {
+                if Engine::envelope_lower_bound_m(lon, lat, &candidate.envelope) > best_distance_m {
+                    continue;
+                }
+            }
+
+            let distance_m = self.distance_to_polygon_m(lat, lon, candidate.index);
+            if best.map_or(true, |(_, best_distance_m)| distance_m < best_distance_m) {
+                best = Some((candidate.index, distance_m));
+            }
+        }
+
+        let (index, distance_m) = best.expect("R-tree unexpectedly yielded no candidates");
+        (index as i32, distance_m)
+    }
+
+    /// Returns every polygon index within `max_m` metres of a query point
+    /// (zero distance if the point is contained). As in `nearest`, the
+    /// R-tree's iteration order (planar degree distance) doesn't agree
+    /// with metres distance across latitudes, so every candidate is
+    /// visited; `envelope_lower_bound_m` is only used to skip the exact
+    /// distance calculation, never to stop the search
+    fn within_distance(&self, lat: f64, lon: f64, max_m: f64) -> Vec<i32> {
+        let mut hits = Vec::new();
+
+        for candidate in self.rtree.nearest_neighbor_iter(&[lon, lat]) {
+            if Engine::envelope_lower_bound_m(lon, lat, &candidate.envelope) > max_m {
+                continue;
+            }
+
+            let distance_m = self.distance_to_polygon_m(lat, lon, candidate.index);
+            if distance_m <= max_m {
+                hits.push(candidate.index as i32);
+            }
+        }
+
+        hits.sort_unstable();
+        hits
+    }
 }
 
 /// Implements the Python module pip, registers the class Engine